@@ -1,61 +1,173 @@
+mod session;
+
 use fuser::{
-    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
-    ReplyOpen, ReplyWrite, Request, TimeOrNow,
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyCreate, ReplyData,
+    ReplyDirectory, ReplyEmpty, ReplyEntry, ReplyOpen, ReplyWrite, Request, TimeOrNow,
 };
 
-use ssh2::{Agent, Session, Sftp};
+use ssh2::{Agent, CheckResult, HostKeyType, KnownHostFileKind, Session, Sftp};
 
 use libc::{
-    EACCES, EEXIST, EINVAL, EIO, ENOENT, ENOTDIR, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY, write,
+    EACCES, EEXIST, EINVAL, EIO, ENOENT, ENOTDIR, EROFS, O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY,
+    write,
 };
 
+use serde::{Deserialize, Serialize};
+
 use core::str;
 use std::{
     collections::HashMap,
     env::ArgsOs,
     ffi::OsStr,
     fs::{self, File, OpenOptions},
-    io::{Read, Seek, SeekFrom, Write},
+    io::{BufRead, BufReader, Cursor, Read, Seek, SeekFrom, Write},
     os::unix::fs::MetadataExt,
     path::{Path, PathBuf},
     sync::{Arc, Mutex},
-    time::{Duration, SystemTime},
+    time::{Duration, Instant, SystemTime},
 };
 
 const TTL: Duration = Duration::from_secs(1); // 1 second
+// How long a confirmed-ENOENT lookup is trusted before we re-check the
+// remote, mirroring mountpoint-s3's negative-lookup cache.
+const NEGATIVE_CACHE_TTL: Duration = Duration::from_secs(5);
 const ROOT_INODE: u64 = 1;
 const CACHE_PATH: &str = "/var/tmp/tulfs_cache";
-const PRIVATE_KEY: &str = "/home/tanay24/.ssh/networked_fs";
+const DEFAULT_PRIVATE_KEY: &str = ".ssh/networked_fs";
+// Fixed block size for on-demand range caching - bounded like the
+// MAX_PIPE_CHUNK_SIZE pattern used by distant's data-pipe handler, so a
+// single remote read never has to pull more than this much at once.
+const BLOCK_SIZE: u64 = 128 * 1024;
+
+/**
+ * Authentication / host-verification knobs, sourced from mount options
+ * rather than baked-in constants so the crate works for any user/host.
+ * Cloned into the control-channel thread so a "remount" command can
+ * re-authenticate against a new host with the same policy.
+ */
+#[derive(Clone)]
+struct AuthConfig {
+    key_path: Option<PathBuf>,
+    use_agent: bool,
+    strict_host_key_checking: bool,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        let key_path = std::env::var("HOME")
+            .ok()
+            .map(|home| PathBuf::from(home).join(DEFAULT_PRIVATE_KEY));
+        AuthConfig {
+            key_path,
+            use_agent: true,
+            strict_host_key_checking: true,
+        }
+    }
+}
 
 struct OpenEntry {
     file: File,
     ino: u64,
     flags: u32,
     dirty: bool,
+    last_modified: std::time::Instant, // when `dirty` was last set, for write-back aging
+}
+
+/**
+ * Remote mtime/size captured at the moment a file was last fetched into the
+ * local cache. Compared against a fresh `sftp.stat` on open to decide
+ * whether the cached copy is stale.
+ */
+#[derive(Clone, Copy, Serialize, Deserialize)]
+struct CacheMeta {
+    remote_mtime: u64,
+    remote_size: u64,
+}
+
+/**
+ * On-disk snapshot of the inode<->path index, written (zstd-compressed) to
+ * `tulfs.tree.zst` under the local cache root on unmount and on each
+ * `release`, and reloaded in `TULFS::new` so inode numbers stay stable and
+ * already-cached files are reused across client restarts.
+ */
+#[derive(Default, Serialize, Deserialize)]
+struct PersistedTree {
+    inode_to_path: HashMap<u64, PathBuf>,
+    path_to_inode: HashMap<PathBuf, u64>,
+    // Monotonic counter - never reissue an inode a prior session handed out.
+    next_ino: u64,
+    #[serde(default)]
+    cache_meta: HashMap<u64, CacheMeta>,
+}
+
+/**
+ * Per-inode block presence for the sparse local cache file: which
+ * fixed-size (`BLOCK_SIZE`) blocks have actually been fetched from the
+ * remote, out of the `remote_size`-sized sparse file `open` preallocates.
+ */
+#[derive(Default)]
+struct BlockCacheEntry {
+    remote_size: u64,
+    present: std::collections::HashSet<u64>, // block indices present locally
+}
+
+/**
+ * The remote target a mount is currently pointed at. Lives behind
+ * `State`'s mutex (rather than as a plain `TULFS` field) so a "remount"
+ * command from the control channel can swap it atomically while foreground
+ * FUSE callbacks are mid-flight.
+ */
+struct Backend {
+    user: String,
+    host: String,
+    backing_root: PathBuf,
+    changed_at: Instant, // when this backend was last (re)pointed
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend {
+            user: String::new(),
+            host: String::new(),
+            backing_root: PathBuf::new(),
+            changed_at: Instant::now(),
+        }
+    }
 }
 
 #[derive(Default)]
 struct State {
+    backend: Backend, // current user/host/backing_root, swappable via remount
     inode_to_path: HashMap<u64, PathBuf>,
     path_to_inode: HashMap<PathBuf, u64>,
+    next_ino: u64, // Next inode number to hand out - persisted, never reused
 
     next_fh: u64,                        // Next available file handle
     open_files: HashMap<u64, OpenEntry>, // Map of file handle to OpenEntry
+    cache_meta: HashMap<u64, CacheMeta>, // ino -> remote mtime/size at last fetch
+    block_cache: HashMap<u64, BlockCacheEntry>, // ino -> which blocks are cached locally
+    negative_cache: HashMap<PathBuf, Instant>, // rel path -> when it was confirmed ENOENT
 }
 
 struct TULFS {
-    user: String,
-    host: String,
-    sftp: Sftp,
+    // Wrapped so the background write-back flusher thread can share the
+    // same SFTP channel as the foreground FUSE callbacks.
+    sftp: Arc<Mutex<Sftp>>,
     server_hash: String, // Hash of the server hostname so that multiple instances don't conflict
-    backing_root: PathBuf, // Remote backing root directory
-    st: Arc<Mutex<State>>, // Shared state locked with a mutex
+    st: Arc<Mutex<State>>, // Shared state locked with a mutex - includes the current backend target
+    writeback_interval: Duration, // How long a dirty file may go un-flushed
+    auth: AuthConfig, // Retained so the control channel can re-authenticate on remount
+    read_only: bool, // Snapshot/browsing mode - all mutating ops return EROFS
 }
 
 impl TULFS {
-    fn new(hostname: String, backing_root: PathBuf) -> Self {
-        let st = Arc::new(Mutex::new(State::default()));
-        st.lock().unwrap().next_fh = 1; // Start file handles at 1
+    fn new(
+        hostname: String,
+        backing_root: PathBuf,
+        auth: AuthConfig,
+        writeback_interval: Duration,
+        read_only: bool,
+    ) -> Self {
         let hostname_parts: Vec<String> = hostname.splitn(2, '@').map(|s| s.to_string()).collect();
         if hostname_parts.len() != 2 {
             eprintln!("[ERROR] Hostname must be in the format user@host");
@@ -72,9 +184,9 @@ impl TULFS {
         session
             .handshake()
             .expect("Could not complete SSH handshake");
-        session
-            .userauth_pubkey_file(&user, None, &Path::new(PRIVATE_KEY), None)
-            .expect("Could not authenticate");
+
+        verify_host_key(&session, &host, auth.strict_host_key_checking);
+        authenticate(&session, &user, &auth);
 
         // check if the backing directory is actually a directory on the server using sftp
         let sftp = session.sftp().expect("Could not create SFTP session");
@@ -91,21 +203,174 @@ impl TULFS {
             fs::create_dir_all(&cache_dir).expect("Could not create cache directory");
         }
 
-        TULFS {
-            user,
-            host,
+        // Reload the persisted inode<->path index (if any) so inode numbers
+        // stay stable and already-cached files are reused across restarts.
+        let persisted = Self::load_tree(&server_hash);
+        let st = Arc::new(Mutex::new(match persisted {
+            Some(tree) => {
+                println!(
+                    "Loaded persisted tree for server {}: {} inodes",
+                    server_hash,
+                    tree.inode_to_path.len()
+                );
+                State {
+                    inode_to_path: tree.inode_to_path,
+                    path_to_inode: tree.path_to_inode,
+                    next_ino: tree.next_ino,
+                    cache_meta: tree.cache_meta,
+                    ..State::default()
+                }
+            }
+            None => State {
+                next_ino: ROOT_INODE + 1,
+                ..State::default()
+            },
+        }));
+        {
+            let mut st = st.lock().unwrap();
+            st.next_fh = 1; // Start file handles at 1
+            st.backend = Backend {
+                user,
+                host,
+                backing_root,
+                changed_at: Instant::now(),
+            };
+        }
+
+        let sftp = Arc::new(Mutex::new(sftp));
+
+        let tulfs = TULFS {
             sftp,
             server_hash,
-            backing_root,
             st,
+            writeback_interval,
+            auth,
+            read_only,
+        };
+        if !read_only {
+            // Nothing ever goes dirty in read-only mode, so there's nothing
+            // for the background flusher to do.
+            tulfs.spawn_writeback_flusher();
         }
+        tulfs.spawn_control_channel();
+        tulfs
+    }
+
+    /**
+     * Starts a background thread that periodically scans `open_files` for
+     * entries dirty for longer than `writeback_interval` and flushes them to
+     * the remote while the file stays open. Only briefly snapshots the
+     * candidate (fh, ino) list under the lock, then does the actual I/O
+     * without holding it - `release` still does a final synchronous flush.
+     */
+    fn spawn_writeback_flusher(&self) {
+        let sftp = Arc::clone(&self.sftp);
+        let st = Arc::clone(&self.st);
+        let server_hash = self.server_hash.clone();
+        let interval = self.writeback_interval;
+
+        std::thread::spawn(move || loop {
+            std::thread::sleep(interval);
+
+            let candidates: Vec<(u64, u64)> = {
+                let st = st.lock().unwrap();
+                st.open_files
+                    .iter()
+                    .filter(|(_, entry)| entry.dirty && entry.last_modified.elapsed() >= interval)
+                    .map(|(&fh, entry)| (fh, entry.ino))
+                    .collect()
+            };
+
+            for (fh, ino) in candidates {
+                let (rel, backing_root) = {
+                    let st = st.lock().unwrap();
+                    match st.inode_to_path.get(&ino) {
+                        Some(p) => (
+                            p.strip_prefix("/").unwrap_or(p).to_path_buf(),
+                            st.backend.backing_root.clone(),
+                        ),
+                        None => continue,
+                    }
+                };
+
+                let mut remote_path = backing_root;
+                for component in rel.components() {
+                    remote_path.push(component.as_os_str());
+                }
+                let local_path = PathBuf::from(format!("{}/{}", CACHE_PATH, server_hash)).join(&rel);
+
+                let local_file = match OpenOptions::new().read(true).open(&local_path) {
+                    Ok(f) => f,
+                    Err(_) => continue,
+                };
+
+                // Fill in any un-fetched blocks before the full-file upload
+                // so the timed flush doesn't clobber remote regions the
+                // on-demand block cache never downloaded with zeroed holes.
+                if let Ok(metadata) = local_file.metadata() {
+                    let _ = ensure_blocks_cached_in_backend(
+                        &sftp,
+                        &st,
+                        ino,
+                        &remote_path,
+                        &local_path,
+                        0,
+                        metadata.len(),
+                    );
+                }
+
+                let write_result = (|| -> Result<(), libc::c_int> {
+                    let mut local_file = local_file;
+                    local_file.seek(SeekFrom::Start(0)).map_err(|_| EIO)?;
+                    let mut buffer = Vec::new();
+                    local_file.read_to_end(&mut buffer).map_err(|_| EIO)?;
+
+                    let mut sftp = sftp.lock().unwrap();
+                    let mut remote_file = sftp
+                        .open_mode(
+                            &remote_path,
+                            ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE,
+                            0o644,
+                            ssh2::OpenType::File,
+                        )
+                        .map_err(|_| EIO)?;
+                    remote_file.write_all(&buffer).map_err(|_| EIO)
+                })();
+
+                match write_result {
+                    Ok(()) => {
+                        println!("[writeback] Flushed dirty fh {} (ino {}) to remote", fh, ino);
+                        let mut st = st.lock().unwrap();
+                        if let Some(entry) = st.open_files.get_mut(&fh) {
+                            entry.dirty = false;
+                        }
+                    }
+                    Err(_) => {
+                        eprintln!("[writeback] Failed to flush fh {} (ino {}) to remote", fh, ino);
+                    }
+                }
+            }
+        });
     }
 
     fn is_remote_dir(&self, path: &Path) -> bool {
-        let metadata = self.sftp.stat(path);
+        let metadata = self.sftp.lock().unwrap().stat(path);
         metadata.map(|m| m.is_dir()).unwrap_or(false)
     }
 
+    /**
+     * Maps the SFTP file-type bits in `perm` (the POSIX S_IFMT field) to a
+     * `fuser::FileType`, so symlinks show up as links instead of being
+     * collapsed into directories/regular files.
+     */
+    fn conv_file_kind(perm: u32) -> FileType {
+        match perm & libc::S_IFMT as u32 {
+            x if x == libc::S_IFDIR as u32 => FileType::Directory,
+            x if x == libc::S_IFLNK as u32 => FileType::Symlink,
+            _ => FileType::RegularFile,
+        }
+    }
+
     /**
      * Adds /var/tmp/tulfs_cache/<server_hash> as prefix to path
      */
@@ -120,11 +385,7 @@ impl TULFS {
 
     // Adds backing_root as prefix to path
     fn get_remote_abs_path(&self, rel: &Path) -> PathBuf {
-        println!(
-            "get_remote_abs_path: path = {:?} Backing Root: {:?}",
-            rel, self.backing_root
-        );
-        let mut remote_path = self.backing_root.clone();
+        let mut remote_path = self.st.lock().unwrap().backend.backing_root.clone();
 
         // append each component of path to remote_path
         let str_path = rel.to_str().unwrap_or("");
@@ -133,7 +394,6 @@ impl TULFS {
             remote_path.push(component.as_os_str());
         }
 
-        println!("Remote absolute path: {:?}", remote_path);
         remote_path
     }
 
@@ -145,6 +405,59 @@ impl TULFS {
         }
     }
 
+    fn persisted_tree_path_for(server_hash: &str) -> PathBuf {
+        PathBuf::from(format!("{}/{}/tulfs.tree.zst", CACHE_PATH, server_hash))
+    }
+
+    fn persisted_tree_path(&self) -> PathBuf {
+        Self::persisted_tree_path_for(&self.server_hash)
+    }
+
+    /**
+     * Loads the persisted inode<->path index written by a previous session,
+     * if one exists under this server's cache directory.
+     */
+    fn load_tree(server_hash: &str) -> Option<PersistedTree> {
+        let compressed = fs::read(Self::persisted_tree_path_for(server_hash)).ok()?;
+        let bytes = zstd::stream::decode_all(Cursor::new(compressed)).ok()?;
+        bincode::deserialize(&bytes).ok()
+    }
+
+    /**
+     * Writes the current inode<->path index and cache metadata to disk,
+     * zstd-compressed, so a future session can reload it via `load_tree`.
+     * Called on unmount (`Drop`) and after each `release`.
+     */
+    fn save_tree(&self) {
+        let tree = {
+            let st = self.st.lock().unwrap();
+            PersistedTree {
+                inode_to_path: st.inode_to_path.clone(),
+                path_to_inode: st.path_to_inode.clone(),
+                next_ino: st.next_ino,
+                cache_meta: st.cache_meta.clone(),
+            }
+        };
+
+        let bytes = match bincode::serialize(&tree) {
+            Ok(b) => b,
+            Err(e) => {
+                eprintln!("Failed to serialize persisted tree: {}", e);
+                return;
+            }
+        };
+        let compressed = match zstd::stream::encode_all(Cursor::new(bytes), 0) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Failed to compress persisted tree: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = fs::write(self.persisted_tree_path(), compressed) {
+            eprintln!("Failed to write persisted tree: {}", e);
+        }
+    }
+
     fn root_attr(&self) -> FileAttr {
         let uid = unsafe { libc::getuid() } as u32;
         let gid = unsafe { libc::getgid() } as u32;
@@ -186,10 +499,11 @@ impl TULFS {
             return ino;
         }
 
-        // Generate a new inode number based on the hash of the path
-        let d = md5::compute(resolved_path.unwrap().as_bytes()); // I don't want to bother with inode number collisions 
-        let ino = u64::from_be_bytes([d[0], d[1], d[2], d[3], d[4], d[5], d[6], d[7]]);
+        // Hand out the next inode from the persisted monotonic counter -
+        // never reissue an inode a prior session already handed out.
         let mut st = self.st.lock().unwrap();
+        let ino = st.next_ino;
+        st.next_ino += 1;
         println!("Inserting mapping: ino = {:?} rel_path: {:?}", ino, rel_path);
         st.path_to_inode.insert(PathBuf::from(&rel_path), ino);
         st.inode_to_path.insert(ino, PathBuf::from(&rel_path));
@@ -221,7 +535,9 @@ impl TULFS {
         println!("attr_from_remote: rel = {:?}", rel);
         let full_path = self.get_remote_abs_path(&rel);
         println!("attr_from_remote: full_path = {:?}", full_path);
-        let stat = self.sftp.stat(&full_path).map_err(|_| ENOENT)?;
+        // lstat (not stat) so symlinks are reported as links instead of
+        // being followed and collapsed into their target's type.
+        let stat = self.sftp.lock().unwrap().lstat(&full_path).map_err(|_| ENOENT)?;
         let now = SystemTime::now();
         let uid = stat
             .uid
@@ -232,13 +548,10 @@ impl TULFS {
             .map(|g| g as u32)
             .unwrap_or_else(|| unsafe { libc::getgid() as u32 });
 
-        let is_dir = self.is_remote_dir(&full_path);
-        let kind = if is_dir {
-            FileType::Directory
-        } else {
-            FileType::RegularFile
-        };
-        let perm = stat.perm.unwrap_or(if is_dir { 0o755 } else { 0o644 }) as u16;
+        let raw_perm = stat.perm.unwrap_or(libc::S_IFREG as u32 | 0o644);
+        let kind = Self::conv_file_kind(raw_perm);
+        let is_dir = kind == FileType::Directory;
+        let perm = (raw_perm & 0o7777) as u16;
         let size = if is_dir { 0 } else { stat.size.unwrap_or(0) };
 
         let atime = stat
@@ -269,190 +582,712 @@ impl TULFS {
         })
     }
 
-    fn fetch_file_from_remote(&self, path: &Path) -> Result<File, libc::c_int> {
-        let local_path = self.get_local_abs_path(&path);
-        let remote_path = self.get_remote_abs_path(&path);
-        println!("Fetching file from remote server: {:?}", remote_path);
-        let mut remote_file = match self.sftp.open(&remote_path) {
-            Ok(f) => f,
-            Err(_) => {
-                eprintln!("File not found on remote server: {:?}", remote_path);
-                return Err(ENOENT);
-            }
+    /**
+     * Returns true if the remote copy of `path` has a different mtime/size
+     * than what we recorded the last time it was fetched into the cache.
+     */
+    fn is_cache_stale(&self, path: &Path, ino: u64) -> bool {
+        let meta = match self.st.lock().unwrap().cache_meta.get(&ino).copied() {
+            Some(m) => m,
+            None => return true, // never fetched / no record -> treat as stale
         };
-        let mut local_file = match OpenOptions::new()
+        let remote_path = self.get_remote_abs_path(path);
+        let stat = match self.sftp.lock().unwrap().stat(&remote_path) {
+            Ok(s) => s,
+            Err(_) => return false, // can't reach the server, keep serving cache
+        };
+        stat.mtime.unwrap_or(0) != meta.remote_mtime || stat.size.unwrap_or(0) != meta.remote_size
+    }
+
+    /**
+     * Prepares a sparse local cache file sized to match the remote file,
+     * without downloading any data - blocks are pulled in lazily by
+     * `ensure_blocks_cached` as reads/writes actually touch them. Replaces
+     * the old whole-file `fetch_file_from_remote` on the `open` path.
+     */
+    fn allocate_sparse_cache(&self, rel: &Path, ino: u64) -> Result<File, libc::c_int> {
+        let local_path = self.get_local_abs_path(rel);
+        let remote_path = self.get_remote_abs_path(rel);
+        if let Some(parent) = local_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        let stat = self.sftp.lock().unwrap().stat(&remote_path).map_err(|_| ENOENT)?;
+        let remote_size = stat.size.unwrap_or(0);
+
+        let local_file = OpenOptions::new()
             .create(true)
+            .read(true)
             .write(true)
             .truncate(true)
             .open(&local_path)
-        {
-            Ok(f) => f,
+            .map_err(|_| EIO)?;
+        local_file.set_len(remote_size).map_err(|_| EIO)?;
+
+        let mut st = self.st.lock().unwrap();
+        st.block_cache.insert(
+            ino,
+            BlockCacheEntry {
+                remote_size,
+                present: std::collections::HashSet::new(),
+            },
+        );
+        st.cache_meta.insert(
+            ino,
+            CacheMeta {
+                remote_mtime: stat.mtime.unwrap_or(0),
+                remote_size,
+            },
+        );
+        drop(st);
+
+        Ok(local_file)
+    }
+
+    /**
+     * Ensures every block covering `[offset, offset + len)` is present in
+     * the sparse local cache file, fetching only the blocks that are
+     * missing (bounded `BLOCK_SIZE` reads off the remote `sftp` handle).
+     */
+    fn ensure_blocks_cached(
+        &self,
+        rel: &Path,
+        ino: u64,
+        offset: u64,
+        len: u64,
+    ) -> Result<(), libc::c_int> {
+        let remote_path = self.get_remote_abs_path(rel);
+        let local_path = self.get_local_abs_path(rel);
+        ensure_blocks_cached_in_backend(
+            &self.sftp,
+            &self.st,
+            ino,
+            &remote_path,
+            &local_path,
+            offset,
+            len,
+        )
+    }
+
+    fn copy_from_local_to_remote(
+        &self,
+        local_file: File,
+        remote_path: &Path,
+    ) -> Result<(), libc::c_int> {
+        copy_local_to_remote(&self.sftp, local_file, remote_path)
+    }
+
+    /**
+     * Drops the inode<->path mapping for an (absolute) path that no longer
+     * exists on the remote, e.g. after unlink/rmdir.
+     */
+    fn forget_path(&self, abs_path: &Path) {
+        let rel = abs_path.strip_prefix("/").unwrap_or(abs_path).to_path_buf();
+        let mut st = self.st.lock().unwrap();
+        if let Some(ino) = st.path_to_inode.remove(&rel) {
+            st.inode_to_path.remove(&ino);
+        }
+    }
+
+    /**
+     * Returns true if `rel` was confirmed ENOENT on the remote within the
+     * last `NEGATIVE_CACHE_TTL`, so callers can skip the round-trip.
+     */
+    fn is_negatively_cached(&self, rel: &Path) -> bool {
+        match self.st.lock().unwrap().negative_cache.get(rel) {
+            Some(at) => at.elapsed() < NEGATIVE_CACHE_TTL,
+            None => false,
+        }
+    }
+
+    fn record_negative(&self, rel: &Path) {
+        self.st
+            .lock()
+            .unwrap()
+            .negative_cache
+            .insert(rel.to_path_buf(), Instant::now());
+    }
+
+    /**
+     * Evicts a negative-cache entry, e.g. because `rel` was just created,
+     * renamed into, or written - so a stale ENOENT can't shadow it.
+     */
+    fn evict_negative(&self, rel: &Path) {
+        self.st.lock().unwrap().negative_cache.remove(rel);
+    }
+
+    /**
+     * Synchronously flushes every dirty open file to the *current* backend,
+     * via `flush_dirty_files_to_backend` - the same routine the control
+     * channel runs ahead of a remount so in-flight writes land on the old
+     * target before `self.st`'s backend is swapped out from under them.
+     */
+    fn flush_dirty_files(&self) {
+        flush_dirty_files_to_backend(&self.sftp, &self.st, &self.server_hash);
+    }
+
+    /**
+     * Spawns a listener on a unix-domain control socket (under this
+     * server's cache directory) that accepts a single-line `REMOUNT
+     * <user@host:path>` command. On receipt it flushes dirty files to the
+     * current backend, connects to the new target, and atomically swaps
+     * `self.st.lock().unwrap().backend` - open file handles are unaffected
+     * and are re-pointed at the new backend on their next FUSE call. This
+     * mirrors the backend-collection remount nydusd exposes.
+     */
+    fn spawn_control_channel(&self) {
+        let sftp = Arc::clone(&self.sftp);
+        let st = Arc::clone(&self.st);
+        let auth = self.auth.clone();
+        let server_hash = self.server_hash.clone();
+        let socket_path =
+            PathBuf::from(format!("{}/{}/control.sock", CACHE_PATH, self.server_hash));
+
+        let _ = fs::remove_file(&socket_path);
+        let listener = match std::os::unix::net::UnixListener::bind(&socket_path) {
+            Ok(l) => l,
+            Err(e) => {
+                eprintln!("[control] Could not bind control socket {:?}: {}", socket_path, e);
+                return;
+            }
+        };
+        println!("[control] Listening for remount commands on {:?}", socket_path);
+
+        std::thread::spawn(move || {
+            for conn in listener.incoming() {
+                let mut stream = match conn {
+                    Ok(s) => s,
+                    Err(_) => continue,
+                };
+
+                let mut line = String::new();
+                if BufReader::new(&stream).read_line(&mut line).is_err() {
+                    continue;
+                }
+                let line = line.trim();
+
+                let Some(target) = line.strip_prefix("REMOUNT ") else {
+                    let _ = writeln!(stream, "ERR unrecognized command");
+                    continue;
+                };
+
+                let Some((hostname, directory_path)) = extract_hostname_and_path(target) else {
+                    let _ = writeln!(stream, "ERR expected user@host:path");
+                    continue;
+                };
+                let hostname_parts: Vec<&str> = hostname.splitn(2, '@').collect();
+                if hostname_parts.len() != 2 {
+                    let _ = writeln!(stream, "ERR hostname must be user@host");
+                    continue;
+                }
+                let (user, host) = (hostname_parts[0], hostname_parts[1]);
+                let backing_root = PathBuf::from(directory_path);
+
+                // Flush writes to the old backend before we repoint it.
+                flush_dirty_files_to_backend(&sftp, &st, &server_hash);
+
+                let connect_result = (|| -> Result<Sftp, String> {
+                    let tcp = std::net::TcpStream::connect((host, 22))
+                        .map_err(|e| format!("tcp connect failed: {}", e))?;
+                    let mut session =
+                        Session::new().map_err(|e| format!("session init failed: {}", e))?;
+                    session.set_tcp_stream(tcp);
+                    session
+                        .handshake()
+                        .map_err(|e| format!("handshake failed: {}", e))?;
+
+                    verify_host_key(&session, host, auth.strict_host_key_checking);
+                    authenticate(&session, user, &auth);
+
+                    let sftp = session
+                        .sftp()
+                        .map_err(|e| format!("sftp init failed: {}", e))?;
+                    let backing_metadata = sftp.stat(&backing_root);
+                    if backing_metadata.is_err() || !backing_metadata.unwrap().is_dir() {
+                        return Err(format!(
+                            "{:?} is not a valid directory on {}",
+                            backing_root, host
+                        ));
+                    }
+                    Ok(sftp)
+                })();
+
+                match connect_result {
+                    Ok(new_sftp) => {
+                        *sftp.lock().unwrap() = new_sftp;
+                        let mut st = st.lock().unwrap();
+                        st.backend = Backend {
+                            user: user.to_string(),
+                            host: host.to_string(),
+                            backing_root,
+                            changed_at: Instant::now(),
+                        };
+                        drop(st);
+                        println!("[control] Remounted onto {}@{}", user, host);
+                        let _ = writeln!(stream, "OK");
+                    }
+                    Err(e) => {
+                        eprintln!("[control] Remount failed: {}", e);
+                        let _ = writeln!(stream, "ERR {}", e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+impl Drop for TULFS {
+    fn drop(&mut self) {
+        println!("Unmounting TULFS, persisting inode tree for server {}", self.server_hash);
+        self.save_tree();
+    }
+}
+
+impl Filesystem for TULFS {
+    fn init(
+        &mut self,
+        _req: &Request<'_>,
+        _config: &mut fuser::KernelConfig,
+    ) -> Result<(), libc::c_int> {
+        print!("init\n");
+        self.ensure_root();
+        Ok(())
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
+        println!("getattr");
+        println!("ino: {}", ino);
+        if ino == ROOT_INODE {
+            reply.attr(&TTL, &self.root_attr());
+            return;
+        } else {
+            let Some(path) = self.path_for_inode(ino) else {
+                reply.error(ENOENT);
+                return;
+            };
+
+            println!("Path for inode {}: {:?}", ino, path);
+            let rel = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+            if self.is_negatively_cached(&rel) {
+                reply.error(ENOENT);
+                return;
+            }
+            match self.attr_from_remote(rel.clone(), ino) {
+                Ok(attr) => reply.attr(&TTL, &attr),
+                Err(e) => {
+                    if e == ENOENT {
+                        self.record_negative(&rel);
+                    }
+                    reply.error(e)
+                }
+            }
+        }
+    }
+
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        print!("lookup\n");
+        println!("parent: {}, name: {:?}", parent, name);
+
+        // check if parent inode exists
+        if !self.path_for_inode(parent).is_some() {
+            reply.error(ENOENT);
+            return;
+        }
+
+        let parent_path = match self.path_for_inode(parent) {
+            // Get parent path from inode
+            Some(p) => p,
+            None => {
+                reply.error(ENOENT); // Orphaned file? Something is fs wrong
+                return;
+            }
+        };
+
+        // check if file is open in open_files
+        let child_path = parent_path.join(name);
+        let rel = child_path
+            .strip_prefix("/")
+            .unwrap_or(&child_path)
+            .to_path_buf();
+
+        println!("Child path: {:?}", child_path);
+        if self.is_negatively_cached(&rel) {
+            println!("Negative cache hit for {:?}", rel);
+            reply.error(ENOENT);
+            return;
+        }
+
+        let ino = self.inode_for_path(&child_path);
+        if let Some(attr) = self.attr_from_remote(rel.clone(), ino).ok() {
+            reply.entry(&TTL, &attr, 0); // We are not reusing inode numbers keep generation to 0 for now
+        } else {
+            println!("File not found on remote server");
+            self.record_negative(&rel);
+            reply.error(ENOENT);
+        }
+    }
+
+    fn readlink(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyData) {
+        print!("readlink\n");
+        println!("ino: {}", ino);
+
+        let Some(path) = self.path_for_inode(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let rel = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        match self.sftp.lock().unwrap().readlink(&remote_path) {
+            Ok(target) => reply.data(target.to_string_lossy().as_bytes()),
             Err(_) => {
-                eprintln!("Failed to open local file: {:?}", local_path);
-                return Err(EIO);
+                eprintln!("Failed to readlink remote path: {:?}", remote_path);
+                reply.error(EIO);
             }
+        }
+    }
+
+    fn symlink(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        link_name: &OsStr,
+        target: &Path,
+        reply: ReplyEntry,
+    ) {
+        print!("symlink\n");
+        println!("parent: {}, link_name: {:?}, target: {:?}", parent, link_name, target);
+
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(parent_path) = self.path_for_inode(parent) else {
+            reply.error(ENOENT);
+            return;
         };
+        let link_path = parent_path.join(link_name);
+        let rel = link_path.strip_prefix("/").unwrap_or(&link_path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
 
-        println!("Copying file to local cache: {:?}", local_path);
-        if let Err(_) = std::io::copy(&mut remote_file, &mut local_file) {
-            eprintln!("Failed to copy file to local cache: {:?}", local_path);
-            return Err(EIO);
+        if let Err(_) = self.sftp.lock().unwrap().symlink(target, &remote_path) {
+            eprintln!("Failed to create remote symlink: {:?}", remote_path);
+            reply.error(EIO);
+            return;
         }
 
-        // Ensure data is flushed to disk
-        if let Err(_) = local_file.flush() {
-            eprintln!("Failed to flush local file: {:?}", local_path);
-            return Err(EIO);
+        self.evict_negative(&rel);
+        let ino = self.inode_for_path(&link_path);
+        match self.attr_from_remote(rel, ino) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
         }
+    }
 
-        Ok(local_file)
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        print!("readdir\n");
+        println!("ino: {}, offset: {}", ino, offset);
+
+        let Some(path) = self.path_for_inode(ino) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let rel = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        let children = match self.sftp.lock().unwrap().readdir(&remote_path) {
+            Ok(entries) => entries,
+            Err(_) => {
+                eprintln!("Failed to readdir remote path: {:?}", remote_path);
+                reply.error(EIO);
+                return;
+            }
+        };
+
+        let mut entries: Vec<(u64, FileType, String)> = vec![
+            (ino, FileType::Directory, ".".to_string()),
+            (ino, FileType::Directory, "..".to_string()),
+        ];
+
+        for (child_remote_path, stat) in children {
+            let Some(name) = child_remote_path.file_name() else {
+                continue;
+            };
+            let child_rel = rel.join(name);
+            let child_ino = self.inode_for_path(&PathBuf::from("/").join(&child_rel));
+            let kind = Self::conv_file_kind(stat.perm.unwrap_or(libc::S_IFREG as u32));
+            entries.push((child_ino, kind, name.to_string_lossy().to_string()));
+        }
+
+        for (i, entry) in entries.into_iter().enumerate().skip(offset as usize) {
+            // reply.add returns true when the reply buffer is full
+            if reply.add(entry.0, (i + 1) as i64, entry.1, entry.2) {
+                break;
+            }
+        }
+        reply.ok();
     }
 
-    fn copy_from_local_to_remote(
-        &self,
-        mut local_file: File,
-        remote_path: &Path,
-    ) -> Result<(), libc::c_int> {
-        // Rewind local file to start
-        if let Err(_) = local_file.seek(SeekFrom::Start(0)) {
-            eprintln!("Failed to seek local file to start");
-            return Err(EIO);
+    fn mkdir(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        reply: ReplyEntry,
+    ) {
+        print!("mkdir\n");
+        println!("parent: {}, name: {:?}, mode: {:o}", parent, name, mode);
+
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(parent_path) = self.path_for_inode(parent) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let child_path = parent_path.join(name);
+        let rel = child_path.strip_prefix("/").unwrap_or(&child_path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        if let Err(_) = self.sftp.lock().unwrap().mkdir(&remote_path, mode as i32) {
+            eprintln!("Failed to mkdir on remote server: {:?}", remote_path);
+            reply.error(EIO);
+            return;
+        }
+
+        self.evict_negative(&rel);
+        let ino = self.inode_for_path(&child_path);
+        match self.attr_from_remote(rel, ino) {
+            Ok(attr) => reply.entry(&TTL, &attr, 0),
+            Err(e) => reply.error(e),
+        }
+    }
+
+    fn rmdir(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        print!("rmdir\n");
+        println!("parent: {}, name: {:?}", parent, name);
+
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(parent_path) = self.path_for_inode(parent) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let child_path = parent_path.join(name);
+        let rel = child_path.strip_prefix("/").unwrap_or(&child_path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        if let Err(_) = self.sftp.lock().unwrap().rmdir(&remote_path) {
+            eprintln!("Failed to rmdir on remote server: {:?}", remote_path);
+            reply.error(EIO);
+            return;
+        }
+
+        self.forget_path(&child_path);
+        reply.ok();
+    }
+
+    fn unlink(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEmpty) {
+        print!("unlink\n");
+        println!("parent: {}, name: {:?}", parent, name);
+
+        if self.read_only {
+            reply.error(EROFS);
+            return;
         }
 
-        // Open remote file for writing
-        let mut remote_file = match self.sftp.open_mode(
+        let Some(parent_path) = self.path_for_inode(parent) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let child_path = parent_path.join(name);
+        let rel = child_path.strip_prefix("/").unwrap_or(&child_path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        if let Err(_) = self.sftp.lock().unwrap().unlink(&remote_path) {
+            eprintln!("Failed to unlink on remote server: {:?}", remote_path);
+            reply.error(EIO);
+            return;
+        }
+
+        let local_path = self.get_local_abs_path(&rel);
+        if local_path.exists() {
+            let _ = fs::remove_file(&local_path);
+        }
+
+        self.forget_path(&child_path);
+        reply.ok();
+    }
+
+    fn create(
+        &mut self,
+        _req: &Request<'_>,
+        parent: u64,
+        name: &OsStr,
+        mode: u32,
+        _umask: u32,
+        flags: i32,
+        reply: ReplyCreate,
+    ) {
+        print!("create\n");
+        println!(
+            "parent: {}, name: {:?}, mode: {:o}, flags: {}",
+            parent, name, mode, flags
+        );
+
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let Some(parent_path) = self.path_for_inode(parent) else {
+            reply.error(ENOENT);
+            return;
+        };
+        let child_path = parent_path.join(name);
+        let rel = child_path.strip_prefix("/").unwrap_or(&child_path).to_path_buf();
+        let remote_path = self.get_remote_abs_path(&rel);
+
+        let remote_file = self.sftp.lock().unwrap().open_mode(
             &remote_path,
             ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE,
-            0o644,
+            mode as i32,
             ssh2::OpenType::File,
-        ) {
+        );
+        if let Err(_) = remote_file {
+            eprintln!("Failed to create remote file: {:?}", remote_path);
+            reply.error(EIO);
+            return;
+        }
+        drop(remote_file);
+        self.evict_negative(&rel);
+
+        let local_path = self.get_local_abs_path(&rel);
+        if let Some(parent_dir) = local_path.parent() {
+            if let Err(_) = fs::create_dir_all(parent_dir) {
+                reply.error(EIO);
+                return;
+            }
+        }
+        let local_file = match OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&local_path)
+        {
             Ok(f) => f,
             Err(_) => {
-                eprintln!("Failed to open remote file: {:?}", remote_path);
-                return Err(EIO);
+                reply.error(EIO);
+                return;
             }
         };
 
-        // Copy data from local file to remote file
-        let mut buffer = Vec::new();
-        if let Err(_) = local_file.read_to_end(&mut buffer) {
-            eprintln!("Failed to read local file");
-            return Err(EIO);
-        }
-
-        println!("Buffer Contents: {:?}", buffer);
-
-        if let Err(_) = remote_file.write_all(&buffer) {
-            eprintln!("Failed to write to remote file: {:?}", remote_path);
-            return Err(EIO);
-        }
-
-        Ok(())
-    }
+        let ino = self.inode_for_path(&child_path);
+        let fh = {
+            let mut st = self.st.lock().unwrap();
+            let fh = st.next_fh;
+            st.open_files.insert(
+                fh,
+                OpenEntry {
+                    file: local_file,
+                    ino,
+                    flags: flags as u32,
+                    dirty: false,
+                    last_modified: std::time::Instant::now(),
+                },
+            );
+            st.next_fh += 1;
+            fh
+        };
 
-    fn flush_dirty_files(&self) {
-        let mut st = self.st.lock().unwrap();
-        for (fh, entry) in st.open_files.iter_mut() {
-            if entry.dirty {
-                // flush to remote server
-                let _ino = entry.ino;
-                let path = match self.path_for_inode(_ino) {
-                    Some(p) => p,
-                    None => {
-                        eprintln!("Could not find path for inode {}", _ino);
-                        continue;
-                    }
-                };
-                let remote_path = self.get_remote_abs_path(&path);
-                let local_path = self.get_local_abs_path(&path);
-                println!("Flushing dirty file to remote server: {:?}", remote_path);
-                let local_file = match OpenOptions::new().read(true).open(&local_path) {
-                    Ok(f) => f,
-                    Err(_) => {
-                        eprintln!("Failed to open local file: {:?}", local_path);
-                        continue;
-                    }
-                };
-                if let Err(e) = self.copy_from_local_to_remote(local_file, &remote_path) {
-                    eprintln!("Failed to copy file to remote server: {:?}", remote_path);
-                    continue;
-                }
-            }
+        match self.attr_from_remote(rel, ino) {
+            Ok(attr) => reply.created(&TTL, &attr, 0, fh, flags as u32),
+            Err(e) => reply.error(e),
         }
     }
-}
 
-impl Filesystem for TULFS {
-    fn init(
+    fn rename(
         &mut self,
         _req: &Request<'_>,
-        _config: &mut fuser::KernelConfig,
-    ) -> Result<(), libc::c_int> {
-        print!("init\n");
-        self.ensure_root();
-        Ok(())
-    }
+        parent: u64,
+        name: &OsStr,
+        newparent: u64,
+        newname: &OsStr,
+        _flags: u32,
+        reply: ReplyEmpty,
+    ) {
+        print!("rename\n");
+        println!(
+            "parent: {}, name: {:?}, newparent: {}, newname: {:?}",
+            parent, name, newparent, newname
+        );
 
-    fn getattr(&mut self, _req: &Request<'_>, ino: u64, reply: ReplyAttr) {
-        println!("getattr");
-        println!("ino: {}", ino);
-        if ino == ROOT_INODE {
-            reply.attr(&TTL, &self.root_attr());
+        if self.read_only {
+            reply.error(EROFS);
             return;
-        } else {
-            let Some(path) = self.path_for_inode(ino) else {
-                reply.error(ENOENT);
-                return;
-            };
-
-            println!("Path for inode {}: {:?}", ino, path);
-            let rel = path.strip_prefix("/").unwrap_or(&path);
-            match self.attr_from_remote(rel.to_path_buf(), ino) {
-                Ok(attr) => reply.attr(&TTL, &attr),
-                Err(e) => reply.error(e),
-            }
         }
-    }
-
-    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
-        print!("lookup\n");
-        println!("parent: {}, name: {:?}", parent, name);
 
-        // check if parent inode exists
-        if !self.path_for_inode(parent).is_some() {
+        let (Some(parent_path), Some(new_parent_path)) =
+            (self.path_for_inode(parent), self.path_for_inode(newparent))
+        else {
             reply.error(ENOENT);
             return;
+        };
+        let old_path = parent_path.join(name);
+        let new_path = new_parent_path.join(newname);
+        let old_rel = old_path.strip_prefix("/").unwrap_or(&old_path).to_path_buf();
+        let new_rel = new_path.strip_prefix("/").unwrap_or(&new_path).to_path_buf();
+        let old_remote = self.get_remote_abs_path(&old_rel);
+        let new_remote = self.get_remote_abs_path(&new_rel);
+
+        if let Err(_) = self.sftp.lock().unwrap().rename(&old_remote, &new_remote, None) {
+            eprintln!(
+                "Failed to rename remote path {:?} -> {:?}",
+                old_remote, new_remote
+            );
+            reply.error(EIO);
+            return;
         }
 
-        let parent_path = match self.path_for_inode(parent) {
-            // Get parent path from inode
-            Some(p) => p,
-            None => {
-                reply.error(ENOENT); // Orphaned file? Something is fs wrong
-                return;
+        // Move the local cache file alongside the remote rename, if present.
+        let old_local = self.get_local_abs_path(&old_rel);
+        let new_local = self.get_local_abs_path(&new_rel);
+        if old_local.exists() {
+            if let Some(parent_dir) = new_local.parent() {
+                let _ = fs::create_dir_all(parent_dir);
             }
-        };
-
-        // check if file is open in open_files
-        let child_path = parent_path.join(name);
-        let ino = self.inode_for_path(&child_path);
+            let _ = fs::rename(&old_local, &new_local);
+        }
 
-        println!("Child path: {:?}", child_path);
-        if let Some(attr) = self
-            .attr_from_remote(
-                child_path
-                    .strip_prefix("/")
-                    .unwrap_or(&child_path)
-                    .to_path_buf(),
-                ino,
-            )
-            .ok()
-        {
-            reply.entry(&TTL, &attr, 0); // We are not reusing inode numbers keep generation to 0 for now
-        } else {
-            println!("File not found on remote server");
-            reply.error(ENOENT);
+        // Keep inode<->path mappings consistent: the inode survives the rename.
+        let mut st = self.st.lock().unwrap();
+        if let Some(ino) = st.path_to_inode.remove(&old_rel) {
+            st.path_to_inode.insert(new_rel.clone(), ino);
+            st.inode_to_path.insert(ino, new_rel.clone());
         }
+        // `new_rel` now exists and `old_rel` doesn't - but old_rel isn't
+        // negatively cached here (we just removed it), and new_rel must not
+        // be shadowed by a stale ENOENT from before the rename.
+        st.negative_cache.remove(&new_rel);
+        drop(st);
+
+        reply.ok();
     }
 
     fn open(&mut self, _req: &Request<'_>, _ino: u64, _flags: i32, reply: ReplyOpen) {
@@ -479,9 +1314,16 @@ impl Filesystem for TULFS {
         println!("Local path: {:?}", local_path);
         let mut _fh = 0;
         let mut local_flags = _flags as u32;
+        if self.read_only {
+            // Snapshot mode: every handle is read-only, so it can never
+            // become dirty-capable regardless of what the caller asked for.
+            local_flags = O_RDONLY as u32;
+        }
         if !local_path.exists() {
-            std::fs::create_dir_all(local_path.parent().unwrap()).unwrap();
-            let res = self.fetch_file_from_remote(&path);
+            // Lazily-populated sparse cache: no remote bytes are pulled
+            // here, `read`/`write` fetch only the blocks they actually
+            // touch via `ensure_blocks_cached`.
+            let res = self.allocate_sparse_cache(&path, _ino);
             if let Err(e) = res {
                 reply.error(e);
                 return;
@@ -495,6 +1337,7 @@ impl Filesystem for TULFS {
                 flags: local_flags,
                 dirty: false,
                 ino: _ino,
+                last_modified: std::time::Instant::now(),
             };
             _fh = st.next_fh;
             st.open_files.insert(_fh, open_entry);
@@ -505,14 +1348,48 @@ impl Filesystem for TULFS {
         } else {
             let accmode = _flags & O_ACCMODE;
             let mut write_access = accmode == O_WRONLY || accmode == O_RDWR;
-            // check if _ino is already opened with incompatible flags
+            // check if _ino is already opened with incompatible flags.
+            // `open_files` is keyed by file handle, not inode, so scan for
+            // any handle on this inode rather than looking one up by `_ino`.
             let st = self.st.lock().unwrap();
-            if let Some(existing_entry) = st.open_files.get(&_ino) {
-                if existing_entry.dirty {
-                    write_access = false;
-                }
+            let is_dirty = st.open_files.values().any(|entry| entry.ino == _ino && entry.dirty);
+            if is_dirty {
+                write_access = false;
             }
             drop(st);
+
+            // `block_cache`'s present-block set is in-memory only, while
+            // `cache_meta` is persisted - so after a restart (or the first
+            // open of this inode in a fresh process), the on-disk cache
+            // file can exist with a matching mtime/size yet no
+            // `block_cache` entry at all. Treat that the same as "stale":
+            // without a present-block set, every hole in the sparse file
+            // would otherwise be served as real zero bytes instead of
+            // being fetched.
+            let block_cache_missing = {
+                let st = self.st.lock().unwrap();
+                !st.block_cache.contains_key(&_ino)
+            };
+
+            // Close-to-open consistency: revalidate the cache against the
+            // remote mtime/size before handing out a handle.
+            if self.is_cache_stale(&path, _ino) || block_cache_missing {
+                if is_dirty {
+                    eprintln!(
+                        "Write conflict: {:?} changed remotely while local copy has unflushed writes",
+                        path
+                    );
+                    reply.error(EIO);
+                    return;
+                }
+                // Re-size the sparse cache to the new remote length and
+                // drop all presence bits so stale blocks are re-fetched
+                // lazily instead of trusting what's on disk.
+                if let Err(e) = self.allocate_sparse_cache(&path, _ino) {
+                    reply.error(e);
+                    return;
+                }
+            }
             // if write access is false, remove write flags from local_flags
             if !write_access {
                 local_flags &= !(O_WRONLY as u32);
@@ -537,6 +1414,7 @@ impl Filesystem for TULFS {
                 flags: local_flags,
                 ino: _ino,
                 dirty: false,
+                last_modified: std::time::Instant::now(),
             };
             _fh = st.next_fh;
             st.open_files.insert(_fh, open_entry);
@@ -571,6 +1449,36 @@ impl Filesystem for TULFS {
             lock_owner
         );
 
+        if self.read_only {
+            reply.error(EROFS);
+            return;
+        }
+
+        let entry_ino = {
+            let st = self.st.lock().unwrap();
+            match st.open_files.get(&fh) {
+                Some(entry) => {
+                    let accmode = entry.flags & O_ACCMODE as u32;
+                    if accmode != O_WRONLY as u32 && accmode != O_RDWR as u32 {
+                        reply.error(EACCES);
+                        return;
+                    }
+                    entry.ino
+                }
+                None => {
+                    reply.error(EINVAL);
+                    return;
+                }
+            }
+        };
+
+        // Pull in any blocks this write partially overlaps before
+        // overwriting them, so untouched bytes in the same block survive.
+        if let Some(path) = self.path_for_inode(entry_ino) {
+            let rel = path.strip_prefix("/").unwrap_or(&path);
+            let _ = self.ensure_blocks_cached(rel, entry_ino, offset as u64, data.len() as u64);
+        }
+
         let mut st = self.st.lock().unwrap();
         let open_entry = match st.open_files.get_mut(&fh) {
             Some(entry) => entry,
@@ -580,13 +1488,6 @@ impl Filesystem for TULFS {
             }
         };
 
-        // Check if the file was opened with write permissions
-        let accmode = open_entry.flags & O_ACCMODE as u32;
-        if accmode != O_WRONLY as u32 && accmode != O_RDWR as u32 {
-            reply.error(EACCES);
-            return;
-        }
-
         // Seek to the specified offset
         if let Err(_) = open_entry.file.seek(SeekFrom::Start(offset as u64)) {
             reply.error(EIO);
@@ -594,12 +1495,26 @@ impl Filesystem for TULFS {
         }
 
         println!("Writing {} bytes at offset {}", data.len(), offset);
-        println!("Data Contents: {:?}", data);
 
         // Write the data
         match open_entry.file.write(data) {
             Ok(bytes_written) => {
                 open_entry.dirty = true; // Mark file as dirty
+                open_entry.last_modified = std::time::Instant::now();
+
+                // Mark the touched blocks present so later reads/writes
+                // don't try to re-fetch data we just wrote locally.
+                let start_block = offset as u64 / BLOCK_SIZE;
+                let end_block = (offset as u64 + bytes_written as u64).saturating_sub(1) / BLOCK_SIZE;
+                if let Some(block_cache) = st.block_cache.get_mut(&entry_ino) {
+                    for block in start_block..=end_block {
+                        block_cache.present.insert(block);
+                    }
+                    block_cache.remote_size = block_cache
+                        .remote_size
+                        .max(offset as u64 + bytes_written as u64);
+                }
+
                 reply.written(bytes_written as u32);
             }
             Err(_) => {
@@ -624,10 +1539,10 @@ impl Filesystem for TULFS {
             "ino: {}, fh: {}, offset: {}, size: {}, flags: {}, lock_owner: {:?}",
             ino, fh, offset, size, flags, lock_owner
         );
-        let mut file = {
+        let (mut file, entry_ino) = {
             let st = self.st.lock().unwrap();
             match st.open_files.get(&fh) {
-                Some(entry) => entry.file.try_clone().unwrap(),
+                Some(entry) => (entry.file.try_clone().unwrap(), entry.ino),
                 None => {
                     reply.error(EINVAL);
                     return;
@@ -635,6 +1550,16 @@ impl Filesystem for TULFS {
             }
         };
 
+        // Fetch only the blocks this read actually covers before serving
+        // from the local cache file.
+        if let Some(path) = self.path_for_inode(entry_ino) {
+            let rel = path.strip_prefix("/").unwrap_or(&path);
+            if let Err(e) = self.ensure_blocks_cached(rel, entry_ino, offset as u64, size as u64) {
+                reply.error(e);
+                return;
+            }
+        }
+
         // Seek to the specified offset
         if let Err(_) = file.seek(SeekFrom::Start(offset as u64)) {
             reply.error(EIO);
@@ -692,6 +1617,14 @@ impl Filesystem for TULFS {
         let path = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
         let remote_path = self.get_remote_abs_path(&path);
         let local_path = self.get_local_abs_path(&path);
+
+        // The local cache file may still have un-fetched blocks (holes) from
+        // the on-demand range cache; pull those in first so the full-file
+        // upload below doesn't clobber them with zeros.
+        if let Ok(metadata) = fs::metadata(&local_path) {
+            let _ = self.ensure_blocks_cached(&path, entry_ino, 0, metadata.len());
+        }
+
         println!("Flushing dirty file to remote server: {:?}", remote_path);
         let local_file = match OpenOptions::new().read(true).open(&local_path) {
             Ok(f) => f,
@@ -755,8 +1688,16 @@ impl Filesystem for TULFS {
         let path  = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
         if is_dirty {
             let path = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+            self.evict_negative(&path);
             let remote_path = self.get_remote_abs_path(&path);
             let local_path = self.get_local_abs_path(&path);
+
+            // Fill in any un-fetched blocks before the full-file upload so
+            // we don't overwrite the remote with zeroed holes.
+            if let Ok(metadata) = fs::metadata(&local_path) {
+                let _ = self.ensure_blocks_cached(&path, entry_ino, 0, metadata.len());
+            }
+
             println!("Flushing dirty file to remote server: {:?}", remote_path);
             let local_file = match OpenOptions::new().read(true).open(&local_path) {
                 Ok(f) => f,
@@ -781,6 +1722,7 @@ impl Filesystem for TULFS {
         let still_open = st.open_files.values().any(|entry| entry.ino == entry_ino);
         if still_open {
             drop(st);
+            self.save_tree();
             reply.ok();
             return;
         }
@@ -788,6 +1730,8 @@ impl Filesystem for TULFS {
         st.inode_to_path.remove(&_ino);
         println!("Removing path to inode mapping for path {:?}", path);
         st.path_to_inode.remove(&path);
+        st.cache_meta.remove(&entry_ino);
+        st.block_cache.remove(&entry_ino);
         drop(st);
 
         // delete the local cached file
@@ -800,12 +1744,206 @@ impl Filesystem for TULFS {
         }
         println!("Deleted local cached file: {:?}", local_path);
 
+        self.save_tree();
         reply.ok();
     }
 }
 
-// ! ISSUE: Right now if I run the test program twice without shutting down the fuse client then 
-// ! I get an error where inode_to_path doesn't contain the inode even though it should.
+
+/**
+ * Free-standing counterpart to `TULFS::ensure_blocks_cached` - same logic,
+ * but taking `sftp`/`st` directly so it can run from background threads
+ * that only hold cloned `Arc`s, without a `&TULFS`. Call this before any
+ * full-file upload of `local_path` (write-back flush, remount-triggered
+ * flush, ...): the on-demand block cache leaves un-fetched ranges of the
+ * local file as holes that read back as zeros, so uploading it as-is would
+ * silently zero out every remote region the caller never touched.
+ */
+fn ensure_blocks_cached_in_backend(
+    sftp: &Mutex<Sftp>,
+    st: &Mutex<State>,
+    ino: u64,
+    remote_path: &Path,
+    local_path: &Path,
+    offset: u64,
+    len: u64,
+) -> Result<(), libc::c_int> {
+    if len == 0 {
+        return Ok(());
+    }
+
+    let remote_size = {
+        let st = st.lock().unwrap();
+        match st.block_cache.get(&ino) {
+            Some(c) => c.remote_size,
+            None => return Ok(()), // nothing registered (e.g. new/empty file)
+        }
+    };
+    if remote_size == 0 {
+        return Ok(());
+    }
+
+    let end = (offset + len).min(remote_size);
+    if offset >= end {
+        return Ok(());
+    }
+    let start_block = offset / BLOCK_SIZE;
+    let end_block = (end - 1) / BLOCK_SIZE;
+
+    let missing: Vec<u64> = {
+        let st = st.lock().unwrap();
+        let present = &st.block_cache.get(&ino).unwrap().present;
+        (start_block..=end_block)
+            .filter(|b| !present.contains(b))
+            .collect()
+    };
+    if missing.is_empty() {
+        return Ok(());
+    }
+
+    let mut remote_file = sftp.lock().unwrap().open(remote_path).map_err(|_| ENOENT)?;
+    let mut local_file = OpenOptions::new().write(true).open(local_path).map_err(|_| EIO)?;
+
+    for block in missing {
+        let block_offset = block * BLOCK_SIZE;
+        let block_len = BLOCK_SIZE.min(remote_size - block_offset) as usize;
+        remote_file.seek(SeekFrom::Start(block_offset)).map_err(|_| EIO)?;
+        let mut buf = vec![0u8; block_len];
+        let mut filled = 0usize;
+        while filled < buf.len() {
+            match remote_file.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(_) => return Err(EIO),
+            }
+        }
+        local_file.seek(SeekFrom::Start(block_offset)).map_err(|_| EIO)?;
+        local_file.write_all(&buf[..filled]).map_err(|_| EIO)?;
+
+        let mut st = st.lock().unwrap();
+        st.block_cache.get_mut(&ino).unwrap().present.insert(block);
+    }
+
+    Ok(())
+}
+
+/**
+ * Overwrites `remote_path` on the server pointed to by `sftp` with the full
+ * contents of `local_file`. Free-standing (rather than a `TULFS` method) so
+ * it can be called from background threads that only hold a cloned
+ * `Arc<Mutex<Sftp>>`, without needing a `&TULFS`.
+ */
+fn copy_local_to_remote(
+    sftp: &Mutex<Sftp>,
+    mut local_file: File,
+    remote_path: &Path,
+) -> Result<(), libc::c_int> {
+    // Rewind local file to start
+    if let Err(_) = local_file.seek(SeekFrom::Start(0)) {
+        eprintln!("Failed to seek local file to start");
+        return Err(EIO);
+    }
+
+    // Open remote file for writing
+    let mut remote_file = match sftp.lock().unwrap().open_mode(
+        &remote_path,
+        ssh2::OpenFlags::WRITE | ssh2::OpenFlags::CREATE | ssh2::OpenFlags::TRUNCATE,
+        0o644,
+        ssh2::OpenType::File,
+    ) {
+        Ok(f) => f,
+        Err(_) => {
+            eprintln!("Failed to open remote file: {:?}", remote_path);
+            return Err(EIO);
+        }
+    };
+
+    // Copy data from local file to remote file
+    let mut buffer = Vec::new();
+    if let Err(_) = local_file.read_to_end(&mut buffer) {
+        eprintln!("Failed to read local file");
+        return Err(EIO);
+    }
+
+    if let Err(_) = remote_file.write_all(&buffer) {
+        eprintln!("Failed to write to remote file: {:?}", remote_path);
+        return Err(EIO);
+    }
+
+    Ok(())
+}
+
+/**
+ * Synchronously flushes every dirty open file to whatever backend `st`
+ * currently points at. Free-standing so it can run from the control-channel
+ * thread (which only holds cloned `Arc`s) as well as from `TULFS` itself,
+ * ahead of a remount repointing `st.backend`.
+ */
+fn flush_dirty_files_to_backend(sftp: &Mutex<Sftp>, st: &Mutex<State>, server_hash: &str) {
+    let candidates: Vec<(u64, u64)> = {
+        let st = st.lock().unwrap();
+        st.open_files
+            .iter()
+            .filter(|(_, entry)| entry.dirty)
+            .map(|(&fh, entry)| (fh, entry.ino))
+            .collect()
+    };
+
+    for (fh, ino) in candidates {
+        let (rel, backing_root) = {
+            let st = st.lock().unwrap();
+            let path = match st.inode_to_path.get(&ino) {
+                Some(p) => p.clone(),
+                None => {
+                    eprintln!("Could not find path for inode {}", ino);
+                    continue;
+                }
+            };
+            let rel = path.strip_prefix("/").unwrap_or(&path).to_path_buf();
+            (rel, st.backend.backing_root.clone())
+        };
+
+        let mut remote_path = backing_root;
+        for component in rel.components() {
+            remote_path.push(component.as_os_str());
+        }
+        let local_path = PathBuf::from(format!("{}/{}", CACHE_PATH, server_hash)).join(&rel);
+
+        // Fill in any un-fetched blocks before the full-file upload so this
+        // flush doesn't clobber remote regions the on-demand block cache
+        // never downloaded with zeroed holes.
+        if let Ok(metadata) = fs::metadata(&local_path) {
+            let _ = ensure_blocks_cached_in_backend(
+                sftp,
+                st,
+                ino,
+                &remote_path,
+                &local_path,
+                0,
+                metadata.len(),
+            );
+        }
+
+        println!("Flushing dirty file to remote server: {:?}", remote_path);
+        let local_file = match OpenOptions::new().read(true).open(&local_path) {
+            Ok(f) => f,
+            Err(_) => {
+                eprintln!("Failed to open local file: {:?}", local_path);
+                continue;
+            }
+        };
+        if copy_local_to_remote(sftp, local_file, &remote_path).is_err() {
+            eprintln!("Failed to copy file to remote server: {:?}", remote_path);
+            continue;
+        }
+
+        let mut st = st.lock().unwrap();
+        st.negative_cache.remove(&rel);
+        if let Some(entry) = st.open_files.get_mut(&fh) {
+            entry.dirty = false;
+        }
+    }
+}
 
 fn extract_hostname_and_path(backing: &str) -> Option<(&str, &str)> {
     if (!backing.contains(':')) {
@@ -819,11 +1957,205 @@ fn extract_hostname_and_path(backing: &str) -> Option<(&str, &str)> {
     }
 }
 
+/**
+ * Verifies the server's host key against `~/.ssh/known_hosts`, refusing to
+ * mount on a mismatch. If the host is unknown and strict checking is
+ * disabled, records it so future connections are verified too.
+ */
+fn verify_host_key(session: &Session, host: &str, strict: bool) {
+    let mut known_hosts = session.known_hosts().expect("Could not create known_hosts");
+    let known_hosts_path = std::env::var("HOME")
+        .map(|home| PathBuf::from(home).join(".ssh/known_hosts"))
+        .expect("Could not determine HOME for known_hosts");
+    let _ = known_hosts.read_file(&known_hosts_path, KnownHostFileKind::OpenSSH);
+
+    let (key, key_type) = session
+        .host_key()
+        .expect("Server did not present a host key");
+
+    match known_hosts.check(host, key) {
+        CheckResult::Match => {}
+        CheckResult::Mismatch => {
+            eprintln!(
+                "[ERROR] Host key for {} does not match known_hosts entry - refusing to mount",
+                host
+            );
+            std::process::exit(1);
+        }
+        CheckResult::NotFound => {
+            if strict {
+                eprintln!(
+                    "[ERROR] Host key for {} is not in known_hosts (strict host key checking is on)",
+                    host
+                );
+                std::process::exit(1);
+            }
+            println!("[WARN] Host key for {} not found, recording it on first connect", host);
+            let hostkey_type = match key_type {
+                HostKeyType::Rsa => ssh2::KnownHostKeyFormat::SshRsa,
+                HostKeyType::Dss => ssh2::KnownHostKeyFormat::SshDss,
+                _ => ssh2::KnownHostKeyFormat::SshRsa,
+            };
+            let _ = known_hosts.add(host, key, "tulfs-auto-added", hostkey_type);
+            let _ = known_hosts.write_file(&known_hosts_path, KnownHostFileKind::OpenSSH);
+        }
+        CheckResult::Failure => {
+            eprintln!("[ERROR] Failed to check host key for {}", host);
+            std::process::exit(1);
+        }
+    }
+}
+
+/**
+ * Tries ssh-agent identities first, then falls back to a configured key
+ * file, then password auth - the same precedence `ssh` itself uses.
+ */
+fn authenticate(session: &Session, user: &str, auth: &AuthConfig) {
+    if auth.use_agent {
+        if let Ok(()) = try_agent_auth(session, user) {
+            return;
+        }
+        println!("[INFO] ssh-agent authentication unavailable or failed, falling back");
+    }
+
+    if let Some(key_path) = &auth.key_path {
+        if key_path.exists() {
+            if session
+                .userauth_pubkey_file(user, None, key_path, None)
+                .is_ok()
+            {
+                return;
+            }
+            println!(
+                "[INFO] Key-file authentication with {:?} failed, falling back to password",
+                key_path
+            );
+        }
+    }
+
+    let password = rpassword_prompt(user);
+    session
+        .userauth_password(user, &password)
+        .expect("Could not authenticate (agent, key, and password all failed)");
+}
+
+fn try_agent_auth(session: &Session, user: &str) -> Result<(), ()> {
+    let mut agent: Agent = session.agent().map_err(|_| ())?;
+    agent.connect().map_err(|_| ())?;
+    agent.list_identities().map_err(|_| ())?;
+    for identity in agent.identities().map_err(|_| ())? {
+        if agent.userauth(user, &identity).is_ok() {
+            return Ok(());
+        }
+    }
+    Err(())
+}
+
+fn rpassword_prompt(user: &str) -> String {
+    print!("Password for {}: ", user);
+    let _ = std::io::stdout().flush();
+    let mut password = String::new();
+    std::io::stdin()
+        .read_line(&mut password)
+        .expect("Could not read password");
+    password.trim_end().to_string()
+}
+
+/**
+ * Parses `-o key=value,key2=value2` style mount options into an
+ * `AuthConfig`, leaving unrecognized keys for the caller to interpret.
+ */
+fn parse_auth_config(raw_opts: &str) -> AuthConfig {
+    let mut auth = AuthConfig::default();
+    for kv in raw_opts.split(',') {
+        let mut parts = kv.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        match key {
+            "key_path" if !value.is_empty() => auth.key_path = Some(PathBuf::from(value)),
+            "agent" => auth.use_agent = value != "no" && value != "false",
+            "strict_host_key_checking" => {
+                auth.strict_host_key_checking = value != "no" && value != "false"
+            }
+            _ => {}
+        }
+    }
+    auth
+}
+
+const DEFAULT_WRITEBACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/**
+ * Parses a `writeback=<N><unit>` mount option (e.g. `writeback=5s`,
+ * `writeback=2m`) out of `-o key=value,...`, defaulting to 30s if absent or
+ * unparsable.
+ */
+fn parse_writeback_interval(raw_opts: &str) -> Duration {
+    for kv in raw_opts.split(',') {
+        let mut parts = kv.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if key != "writeback" || value.is_empty() {
+            continue;
+        }
+        let (num_str, unit) = match value.trim_end_matches(|c: char| c.is_alphabetic()) {
+            num_str if num_str.len() < value.len() => (num_str, &value[num_str.len()..]),
+            num_str => (num_str, "s"),
+        };
+        if let Ok(num) = num_str.parse::<u64>() {
+            return match unit {
+                "ms" => Duration::from_millis(num),
+                "m" => Duration::from_secs(num * 60),
+                _ => Duration::from_secs(num),
+            };
+        }
+    }
+    DEFAULT_WRITEBACK_INTERVAL
+}
+
+/**
+ * Parses a bare `ro` (or `ro=true`) mount option selecting the read-only
+ * snapshot mode: `MountOption::RO` is passed to `fuser`, and every
+ * mutating `Filesystem` callback short-circuits with `EROFS` instead of
+ * touching the remote.
+ */
+fn parse_ro_flag(raw_opts: &str) -> bool {
+    raw_opts.split(',').any(|kv| {
+        let mut parts = kv.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        if key != "ro" {
+            return false;
+        }
+        let value = parts.next().unwrap_or("").trim();
+        value.is_empty() || value == "true" || value == "1"
+    })
+}
+
+/**
+ * Parses a `mt=true` (or `mt=1`) mount option selecting the multi-threaded
+ * session: the mount runs on a background thread (`session::spawn_mount`)
+ * instead of blocking `main` (`session::mount_foreground`), which is the
+ * default.
+ */
+fn parse_mt_flag(raw_opts: &str) -> bool {
+    for kv in raw_opts.split(',') {
+        let mut parts = kv.splitn(2, '=');
+        let key = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("").trim();
+        if key == "mt" {
+            return value == "true" || value == "1";
+        }
+    }
+    false
+}
+
 fn main() {
     let args: Vec<_> = std::env::args_os().skip(1).collect();
     println!("Args {:?}", args);
-    if (args.len() != 2) {
-        eprintln!("Usage: client <mountpoint> <user@host:backing_directory>");
+    if args.len() != 2 && args.len() != 4 {
+        eprintln!(
+            "Usage: client <mountpoint> <user@host:backing_directory> [-o key=val,key2=val2]"
+        );
         std::process::exit(1);
     }
     let arg = args.as_slice();
@@ -836,6 +2168,16 @@ fn main() {
         .and_then(|s| s.to_str())
         .expect("Missing backing directory argument");
 
+    let raw_opts = if args.len() == 4 && arg.get(2).and_then(|s| s.to_str()) == Some("-o") {
+        arg.get(3).and_then(|s| s.to_str()).unwrap_or("")
+    } else {
+        ""
+    };
+    let auth = parse_auth_config(raw_opts);
+    let writeback_interval = parse_writeback_interval(raw_opts);
+    let mt = parse_mt_flag(raw_opts);
+    let read_only = parse_ro_flag(raw_opts);
+
     let res_target_backing = extract_hostname_and_path(backing);
     if res_target_backing.is_none() {
         eprintln!("Backing argument must be in the format hostname:directory_path");
@@ -856,10 +2198,26 @@ fn main() {
         MountOption::AutoUnmount,
         MountOption::DefaultPermissions,
     ];
+    if read_only {
+        opts.push(MountOption::RO);
+    }
+
+    let tulfs = TULFS::new(
+        hostname.to_string(),
+        backing_root,
+        auth,
+        writeback_interval,
+        read_only,
+    );
+    let mountpoint = Path::new(mountpoint);
 
-    let tulfs = TULFS::new(hostname.to_string(), backing_root);
+    let mount_result = if mt {
+        session::spawn_mount(tulfs, mountpoint, &opts).map(session::MountHandle::join)
+    } else {
+        session::mount_foreground(tulfs, mountpoint, &opts)
+    };
 
-    if let Err(err) = fuser::mount2(tulfs, mountpoint, &opts) {
+    if let Err(err) = mount_result {
         eprintln!("Failed to mount filesystem: {}", err);
         std::process::exit(1);
     }
@@ -0,0 +1,51 @@
+//! Thin daemon/session layer around `TULFS`.
+//!
+//! `TULFS` is a plain `fuser::Filesystem` impl with no opinion about how it
+//! gets mounted - this module owns that decision, separating "build the
+//! filesystem" from "drive a FUSE session on a thread", which is what lets
+//! `main` choose between a blocking foreground mount and a background one
+//! it can join later (see the `mt=` option).
+
+use std::io;
+use std::path::Path;
+
+use fuser::{BackgroundSession, MountOption};
+
+use crate::TULFS;
+
+/// A `TULFS` mount running on its own background thread. Dropping the
+/// handle (or calling `join`) waits for `fuser` to unmount.
+pub(crate) struct MountHandle {
+    session: BackgroundSession,
+}
+
+impl MountHandle {
+    /// Blocks until the session unmounts, e.g. via `fusermount -u` or the
+    /// kernel tearing the mount down.
+    pub(crate) fn join(self) {
+        self.session.join();
+    }
+}
+
+/// Mounts `fs` on a background thread and returns immediately with a handle
+/// that can be joined (or dropped to unmount). This is the multi-threaded
+/// counterpart to `mount_foreground` - callers keep running on the calling
+/// thread while FUSE requests are served on the spawned one.
+pub(crate) fn spawn_mount(
+    fs: TULFS,
+    mountpoint: &Path,
+    options: &[MountOption],
+) -> io::Result<MountHandle> {
+    let session = fuser::spawn_mount2(fs, mountpoint, options)?;
+    Ok(MountHandle { session })
+}
+
+/// Mounts `fs` and blocks the calling thread until it's unmounted - what
+/// `main` did directly before this module existed.
+pub(crate) fn mount_foreground(
+    fs: TULFS,
+    mountpoint: &Path,
+    options: &[MountOption],
+) -> io::Result<()> {
+    fuser::mount2(fs, mountpoint, options)
+}
@@ -1,44 +1,411 @@
-use libc::{c_char, c_int, close as c_close, mode_t, open as c_open, O_CREAT, O_RDONLY, O_TRUNC};
-use std::ffi::CString;
+use libc::{
+    c_char, c_int, close as c_close, mode_t, O_CREAT, O_EXCL, O_RDONLY, O_RDWR, O_TRUNC, O_WRONLY,
+};
 use std::env;
+use std::ffi::{CStr, CString, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
 
-fn main() {
-    // Pick path from first CLI arg or default to a likely non-existent file for demo
-    let path = env::args().nth(1).unwrap_or_else(|| String::from("/tmp/demo_open_test.txt"));
+/**
+ * An owned raw file descriptor. `Drop` closes it (retrying on `EINTR`), so
+ * callers can't leak it by forgetting to call `close()` or by returning
+ * early - the same RAII split the standard library's Unix `File` uses
+ * around its inner `FileDesc`.
+ */
+struct FileDesc(c_int);
 
-    // Convert to C-compatible string (nul-terminated)
-    let c_path = match CString::new(path.clone()) {
-        Ok(s) => s,
-        Err(_) => {
-            eprintln!("Path contains interior NUL byte: {}", path);
-            std::process::exit(1);
-        }
-    };
+impl FileDesc {
+    fn raw(&self) -> c_int {
+        self.0
+    }
+
+    /// Releases ownership of the underlying fd without closing it, handing
+    /// the caller responsibility for it instead (e.g. because it's already
+    /// installed at its final destination via `dup2`).
+    fn into_raw(self) -> c_int {
+        let fd = self.0;
+        std::mem::forget(self);
+        fd
+    }
+
+    /// Truncates (or, if `size` is larger than the current length, extends
+    /// with a sparse hole) the file to exactly `size` bytes via
+    /// `ftruncate`. Fails with `EINVAL` for a size the filesystem can't
+    /// represent and `EFBIG` for one past the process's file-size limit -
+    /// both surface as the matching `io::ErrorKind` via `cvt`.
+    fn set_len(&self, size: u64) -> io::Result<()> {
+        #[cfg(target_os = "linux")]
+        let ret = cvt(unsafe { libc::ftruncate64(self.0, size as libc::off64_t) });
+        #[cfg(not(target_os = "linux"))]
+        let ret = cvt(unsafe { libc::ftruncate(self.0, size as libc::off_t) });
+        ret.map(|_| ())
+    }
+}
+
+impl Drop for FileDesc {
+    fn drop(&mut self) {
+        // cvt_r already retries on EINTR; drop can't propagate a close()
+        // failure to a caller, so the result is intentionally discarded.
+        let _ = cvt_r(|| unsafe { c_close(self.0) });
+    }
+}
+
+/// Returns the calling thread's `errno`. libc doesn't expose one portable
+/// symbol for this - each OS names (and sometimes indirects through a
+/// function rather than a plain static) its own, so dispatch per target.
+fn errno() -> c_int {
+    unsafe { *errno_location() }
+}
 
-    // Flags: try read-only first. You can change flags as needed (e.g., O_RDWR|O_CREAT|O_TRUNC)
-    let flags: c_int = O_RDONLY | O_CREAT | O_TRUNC;
-    // If using O_CREAT, set mode appropriately, e.g., 0o644
-    let mode: mode_t = 0o644;
+#[cfg(target_os = "linux")]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno_location()
+}
 
-    // Safety: calling a libc function
-    let fd: c_int = unsafe { c_open(c_path.as_ptr() as *const c_char, flags, mode) };
+#[cfg(any(target_os = "macos", target_os = "ios", target_os = "freebsd"))]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__error()
+}
+
+#[cfg(any(target_os = "netbsd", target_os = "openbsd"))]
+unsafe fn errno_location() -> *mut c_int {
+    libc::__errno()
+}
 
-    if fd < 0 {
-        // Open failed; print the OS error
-        let err = std::io::Error::last_os_error();
-        eprintln!("open() failed for {}: {}", path, err);
-        std::process::exit(1);
+#[cfg(target_os = "solaris")]
+unsafe fn errno_location() -> *mut c_int {
+    libc::___errno()
+}
+
+/// Converts a raw syscall return value into a `Result`, per the `< 0` means
+/// "failed, check errno" convention `open`/`openat`/`close` all share.
+fn cvt(ret: c_int) -> io::Result<c_int> {
+    if ret < 0 {
+        Err(io::Error::from_raw_os_error(errno()))
     } else {
-        println!("open() succeeded for {}, fd = {}", path, fd);
+        Ok(ret)
+    }
+}
+
+/// Like `cvt`, but retries the call for as long as it keeps failing with
+/// `EINTR` - the single place that retry loop lives now, instead of each
+/// syscall wrapper hand-rolling its own.
+fn cvt_r<F: FnMut() -> c_int>(mut f: F) -> io::Result<c_int> {
+    loop {
+        match cvt(f()) {
+            Err(ref e) if e.raw_os_error() == Some(libc::EINTR) => {}
+            result => return result,
+        }
+    }
+}
+
+/**
+ * Builds the `O_*` flag bitmask for `open()`, mirroring the standard
+ * library's Unix `OpenOptions`. `create_new` maps to `O_CREAT | O_EXCL` so
+ * the open fails if the path already exists.
+ */
+#[derive(Clone, Debug)]
+struct OpenOptions {
+    read: bool,
+    write: bool,
+    append: bool,
+    truncate: bool,
+    create: bool,
+    create_new: bool,
+    custom_flags: c_int,
+    mode: mode_t,
+}
+
+impl OpenOptions {
+    fn new() -> Self {
+        OpenOptions {
+            read: false,
+            write: false,
+            append: false,
+            truncate: false,
+            create: false,
+            create_new: false,
+            custom_flags: 0,
+            mode: 0o666,
+        }
+    }
+
+    fn read(&mut self, read: bool) -> &mut Self {
+        self.read = read;
+        self
+    }
+
+    fn write(&mut self, write: bool) -> &mut Self {
+        self.write = write;
+        self
+    }
+
+    fn append(&mut self, append: bool) -> &mut Self {
+        self.append = append;
+        self
+    }
+
+    fn truncate(&mut self, truncate: bool) -> &mut Self {
+        self.truncate = truncate;
+        self
+    }
+
+    fn create(&mut self, create: bool) -> &mut Self {
+        self.create = create;
+        self
+    }
+
+    fn create_new(&mut self, create_new: bool) -> &mut Self {
+        self.create_new = create_new;
+        self
+    }
+
+    /// Escape hatch for flags this builder doesn't model directly (e.g.
+    /// `O_NONBLOCK`, `O_DIRECTORY`). Masked to exclude the access-mode
+    /// bits, which `read`/`write`/`append` already own.
+    fn custom_flags(&mut self, flags: c_int) -> &mut Self {
+        self.custom_flags = flags;
+        self
+    }
+
+    fn mode(&mut self, mode: mode_t) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    fn access_mode(&self) -> io::Result<c_int> {
+        match (self.read, self.write, self.append) {
+            (true, false, false) => Ok(O_RDONLY),
+            (false, true, false) => Ok(O_WRONLY),
+            (true, true, false) => Ok(O_RDWR),
+            (false, _, true) => Ok(O_WRONLY | libc::O_APPEND),
+            (true, _, true) => Ok(O_RDWR | libc::O_APPEND),
+            (false, false, false) => Err(io::Error::from_raw_os_error(libc::EINVAL)),
+        }
+    }
 
-        // Always close the fd when done
-        let rc = unsafe { c_close(fd) };
-        if rc != 0 {
-            let err = std::io::Error::last_os_error();
-            eprintln!("close() failed: {}", err);
+    fn creation_mode(&self) -> io::Result<c_int> {
+        match (self.write, self.append) {
+            (true, false) => {}
+            (false, false) => {
+                if self.truncate || self.create || self.create_new {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+            }
+            (_, true) => {
+                if self.truncate && !self.create_new {
+                    return Err(io::Error::from_raw_os_error(libc::EINVAL));
+                }
+            }
+        }
+
+        Ok(match (self.create, self.truncate, self.create_new) {
+            (false, false, false) => 0,
+            (true, false, false) => O_CREAT,
+            (false, true, false) => O_TRUNC,
+            (true, true, false) => O_CREAT | O_TRUNC,
+            (_, _, true) => O_CREAT | O_EXCL,
+        })
+    }
+
+    fn flags(&self) -> io::Result<c_int> {
+        Ok(libc::O_CLOEXEC
+            | self.access_mode()?
+            | self.creation_mode()?
+            | (self.custom_flags & !libc::O_ACCMODE))
+    }
+
+    /// Assembles the flags and calls `open()`, returning an owned,
+    /// leak-free `FileDesc` on success.
+    fn open(&self, path: &Path) -> io::Result<FileDesc> {
+        self.open_at(CWD, path)
+    }
+
+    /// Like `open`, but resolves `path` relative to `dirfd` via `openat()`
+    /// instead of the process's current working directory. Pass `CWD` for
+    /// `open`'s usual cwd-relative behavior. Lets a caller that already
+    /// holds an open directory fd resolve paths against it without a
+    /// TOCTOU race against a path string.
+    fn open_at(&self, dirfd: c_int, path: &Path) -> io::Result<FileDesc> {
+        let c_path = path_to_cstring(path)?;
+        let flags = self.flags()?;
+
+        let fd = cvt(unsafe {
+            libc::openat(dirfd, c_path.as_ptr() as *const c_char, flags, self.mode as c_int)
+        })?;
+        Ok(FileDesc(fd))
+    }
+}
+
+/// Sentinel for `open_at`'s `dirfd` meaning "relative to the current
+/// working directory", i.e. the same resolution `open()` itself uses.
+const CWD: c_int = libc::AT_FDCWD;
+
+fn path_to_cstring(path: &Path) -> io::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "path contains interior NUL byte")
+    })
+}
+
+/// Creates an unnamed temporary file in `dir` that's removed automatically
+/// once the last fd to it closes - there's no path for another process to
+/// race against, unlike `mkstemp` followed by `unlink`. Relies on Linux's
+/// `O_TMPFILE`.
+#[cfg(target_os = "linux")]
+fn create_tmpfile(dir: &Path, mode: mode_t) -> io::Result<FileDesc> {
+    OpenOptions::new().write(true).custom_flags(libc::O_TMPFILE).mode(mode).open(dir)
+}
+
+/// Portable fallback for platforms without `O_TMPFILE`: creates a named
+/// file via `mkstemp` and immediately unlinks it, so callers still get an
+/// fd with no surviving directory entry.
+#[cfg(not(target_os = "linux"))]
+fn create_tmpfile(dir: &Path, _mode: mode_t) -> io::Result<FileDesc> {
+    let (fd, path) = mkstemp(dir)?;
+    cvt(unsafe { libc::unlink(path_to_cstring(&path)?.as_ptr()) })?;
+    Ok(fd)
+}
+
+/// Creates a uniquely-named file under `dir` via `mkstemp`, returning the
+/// open fd and the chosen path. The trailing `XXXXXX` in the template is
+/// filled in by the OS, and `mkstemp` creates the file atomically (the same
+/// guarantee `O_CREAT | O_EXCL` gives `open`), so there's no separate
+/// existence check to race.
+fn mkstemp(dir: &Path) -> io::Result<(FileDesc, PathBuf)> {
+    let mut template = dir.join("tmpXXXXXX").into_os_string().into_vec();
+    template.push(0);
+
+    let fd = cvt(unsafe { libc::mkstemp(template.as_mut_ptr() as *mut c_char) })?;
+
+    let name_len = template.iter().position(|&b| b == 0).unwrap_or(template.len());
+    let path = PathBuf::from(OsString::from_vec(template[..name_len].to_vec()));
+    Ok((FileDesc(fd), path))
+}
+
+/// Wraps a raw errno in an `io::Error`, carrying `path` in the message so
+/// failures like `ENOENT`/`ENOTDIR`/`ENAMETOOLONG` are traceable back to
+/// the path that caused them. `io::Error::from_raw_os_error` already maps
+/// the errno to the right `ErrorKind` (`NotFound`, etc.).
+fn path_error(err: c_int, path: &Path) -> io::Error {
+    io::Error::new(
+        io::Error::from_raw_os_error(err).kind(),
+        format!("{}: {:?}", io::Error::from_raw_os_error(err), path),
+    )
+}
+
+/// Resolves `path` to an absolute, symlink-free path via `realpath()`,
+/// passing `NULL` as the output buffer so libc allocates one sized exactly
+/// for the result instead of us guessing (and possibly truncating at)
+/// `PATH_MAX`.
+fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    let c_path = path_to_cstring(path)?;
+    let resolved = unsafe { libc::realpath(c_path.as_ptr(), std::ptr::null_mut()) };
+    if resolved.is_null() {
+        return Err(path_error(errno(), path));
+    }
+
+    let bytes = unsafe { CStr::from_ptr(resolved) }.to_bytes().to_vec();
+    unsafe { libc::free(resolved as *mut libc::c_void) };
+    Ok(PathBuf::from(OsString::from_vec(bytes)))
+}
+
+/// Reads the target of the symlink at `path`, growing the buffer and
+/// retrying if the target didn't fit - `readlink` doesn't NUL-terminate or
+/// report how much space it needed, it just truncates silently.
+fn read_link(path: &Path) -> io::Result<PathBuf> {
+    let c_path = path_to_cstring(path)?;
+    let mut cap: usize = 256;
+
+    loop {
+        let mut buf: Vec<u8> = vec![0; cap];
+        let n = unsafe {
+            libc::readlink(c_path.as_ptr(), buf.as_mut_ptr() as *mut c_char, buf.len())
+        };
+        if n < 0 {
+            return Err(path_error(errno(), path));
+        }
+
+        let n = n as usize;
+        if n < cap {
+            buf.truncate(n);
+            return Ok(PathBuf::from(OsString::from_vec(buf)));
+        }
+        cap *= 2;
+    }
+}
+
+/// Reports whether `fd` is currently a valid, open descriptor. Tries
+/// `poll()` first - it can check many fds in one syscall, and a bad fd
+/// comes back with `POLLNVAL` set in `revents` - retrying on `EINTR`.
+/// Falls back to `fcntl(F_GETFD)` on platforms where `poll()` doesn't
+/// reliably report `POLLNVAL` for a bad fd.
+fn fd_is_valid(fd: c_int) -> bool {
+    #[cfg(not(any(target_os = "macos", target_os = "ios", target_os = "redox")))]
+    {
+        let mut pfd = libc::pollfd { fd, events: 0, revents: 0 };
+        loop {
+            let ret = unsafe { libc::poll(&mut pfd, 1, 0) };
+            if ret >= 0 {
+                return pfd.revents & libc::POLLNVAL == 0;
+            }
+            if errno() != libc::EINTR {
+                break;
+            }
+        }
+    }
+
+    let ret = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    ret != -1 || errno() != libc::EBADF
+}
+
+/// Ensures fds 0, 1 and 2 are valid open descriptors before the rest of the
+/// program starts trusting them, e.g. before spawning a child that inherits
+/// them or before any code treats "the fd `open()` just returned" as
+/// necessarily distinct from stdin/stdout/stderr. Any of the three found
+/// closed are replaced with a freshly opened `/dev/null`. Optional - call
+/// it early in `main` for programs that need the hardening; this demo
+/// doesn't strictly need it since it only opens one file itself.
+fn sanitize_std_fds() {
+    for fd in 0..=2 {
+        if fd_is_valid(fd) {
+            continue;
+        }
+        if let Ok(null_fd) = OpenOptions::new().read(true).write(true).open(Path::new("/dev/null"))
+        {
+            if null_fd.raw() == fd {
+                // `open` handed back the exact slot we were trying to
+                // repair (it always returns the lowest free fd, which is
+                // `fd` itself here) - keep it open rather than letting
+                // `Drop` close it right back out from under us. `open` set
+                // `O_CLOEXEC`, so clear it: a std fd must survive `exec`.
+                let _ = cvt_r(|| unsafe { libc::fcntl(null_fd.raw(), libc::F_SETFD, 0) });
+                null_fd.into_raw();
+            } else {
+                let _ = cvt_r(|| unsafe { libc::dup2(null_fd.raw(), fd) });
+                // `null_fd` drops here, closing the temporary descriptor;
+                // `fd` itself now refers to the same `/dev/null` open file
+                // via dup2.
+            }
+        }
+    }
+}
+
+fn main() {
+    sanitize_std_fds();
+
+    // Pick path from first CLI arg or default to a likely non-existent file for demo
+    let path = env::args().nth(1).unwrap_or_else(|| String::from("/tmp/demo_open_test.txt"));
+    let path = Path::new(&path);
+
+    match OpenOptions::new().read(true).write(true).create(true).truncate(true).open(path) {
+        Ok(fd) => {
+            println!("open() succeeded for {:?}, fd = {}", path, fd.raw());
+            // `fd` is dropped here, closing it (and retrying on EINTR).
+        }
+        Err(err) => {
+            eprintln!("open() failed for {:?}: {}", path, err);
             std::process::exit(1);
-        } else {
-            println!("close() succeeded");
         }
     }
 }